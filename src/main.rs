@@ -3,10 +3,11 @@ use std::ffi::OsString;
 use std::fs::{self, File};
 use std::io::{LineWriter, Write};
 use std::path::{Path, PathBuf};
-use std::process::exit;
+use std::process::{exit, Command};
 use clap::Parser;
 use std::error::Error;
 use clap::{arg, command};
+use regex::Regex;
 extern crate globwalk;
 
 #[derive(PartialEq)]
@@ -39,6 +40,16 @@ enum ActionWhenStuckRollingBack {
     AbortRollback,
 }
 
+#[derive(Clone, Copy, PartialEq, Debug, clap::ValueEnum)]
+enum PromptMode {
+    /// Prompt before every rename, not just when one gets stuck
+    Always,
+    /// Only prompt when a rename would overwrite a file or otherwise fails
+    Error,
+    /// Never block for input; skip anything that would need a prompt
+    Never,
+}
+
 macro_rules! die
 {
     ($($arg:expr),+) => {{
@@ -62,16 +73,69 @@ struct Arguments {
     /// Flag to dry-run the file renaming -- with this flag enabled the file-renaming map is simply printed to std-out
     #[arg(short = 'd', long, default_value_t = false)]
     dry_run: bool,
+
+    /// Editor to open the rename buffer with, overriding $VISUAL/$EDITOR
+    #[arg(short = 'e', long)]
+    editor: Option<String>,
+
+    /// When to prompt interactively on a stuck rename
+    #[arg(short = 'p', long, value_enum, default_value_t = PromptMode::Error)]
+    prompt: PromptMode,
+
+    /// Allow a rename to overwrite a file that already exists at the destination
+    #[arg(short = 'o', long, default_value_t = false)]
+    overwrite: bool,
+
+    /// Regex to match against each filename, for scripted renames without an editor. Requires --replace.
+    #[arg(long, requires = "replace")]
+    find: Option<String>,
+
+    /// Replacement template for --find, supporting capture references like $1 or ${name}
+    #[arg(long, requires = "find")]
+    replace: Option<String>,
+
+    /// Normalize filenames into a lowercase, shell-hostile-character-free, tab-completion-friendly form
+    #[arg(long, conflicts_with = "find", default_value_t = false)]
+    sanitize: bool,
+
+    /// When sanitizing, preserve the original case instead of lowercasing
+    #[arg(long, requires = "sanitize", default_value_t = false)]
+    keep_case: bool,
+
+    /// Separator to use in place of whitespace and shell-hostile characters when sanitizing
+    #[arg(long, requires = "sanitize", default_value_t = String::from("_"))]
+    separator: String,
+
+    /// Undo the renames recorded by the most recent batch that renamed anything (including the completed part of an aborted batch), instead of selecting files to rename
+    #[arg(short = 'u', long, conflicts_with_all = ["files", "find", "sanitize"], default_value_t = false)]
+    undo: bool,
 }
 
 fn main() {
     let args = Arguments::parse();
+
+    if args.undo {
+        let mut files = load_rename_journal();
+        execute_rename(&args, &mut files);
+        print_state(&files);
+        return;
+    }
+
     let mut files = list_files(&args);
     handle_degenerate_cases(&args, &files);
 
-    let buffer_filename = std::env::temp_dir().join(".rename_buffer");
-    write_filenames_to_buffer(&buffer_filename, &files);
-    let _ = read_filenames_from_buffer(&buffer_filename, &mut files, &args);
+    if args.find.is_some() {
+        apply_find_replace(&args, &mut files);
+    } else if args.sanitize {
+        apply_sanitize(&args, &mut files);
+    } else {
+        let buffer_filename = std::env::temp_dir().join(".rename_buffer");
+        write_filenames_to_buffer(&buffer_filename, &files);
+        open_editor_on_buffer(&args, &buffer_filename);
+        if let Err(error) = read_filenames_from_buffer(&buffer_filename, &mut files, &args) {
+            die!("{}", error);
+        }
+    }
 
     execute_rename(&args, &mut files);
     print_state(&files);
@@ -170,6 +234,229 @@ fn write_filenames_to_buffer(buffer_filename: &Path, files: &Vec<FileToRename>)
     }
 }
 
+fn rename_journal_path() -> PathBuf {
+    std::env::temp_dir().join(".rename_journal")
+}
+
+// Records every completed rename as `after\tbefore` so a later `--undo` can
+// reverse the batch. Overwriting the journal on each run that renames anything
+// also means undoing an undo redoes the original batch, which falls out of
+// this for free. A run that renames nothing leaves the previous journal as is.
+fn write_rename_journal(files: &[FileToRename]) {
+    let renamed: Vec<&FileToRename> = files
+        .iter()
+        .filter(|f| f.outcome == FileOutcome::Renamed)
+        .collect();
+
+    if renamed.is_empty() {
+        return;
+    }
+
+    let journal_file = match File::create(&rename_journal_path()) {
+        Ok(file) => file,
+        Err(_) => return,
+    };
+    let mut writer = LineWriter::new(journal_file);
+
+    for file in renamed {
+        let _ = writeln!(
+            writer,
+            "{}\t{}",
+            file.full_path_after.display(),
+            file.full_path_before.display()
+        );
+    }
+}
+
+fn load_rename_journal() -> Vec<FileToRename> {
+    let journal_path = rename_journal_path();
+    let content = fs::read_to_string(&journal_path)
+        .unwrap_or_else(|_| die!("No undo journal found at {}.", journal_path.display()));
+
+    let mut files = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut parts = line.splitn(2, '\t');
+        let after = parts
+            .next()
+            .unwrap_or_else(|| die!("Malformed undo journal entry: {}", line));
+        let before = parts
+            .next()
+            .unwrap_or_else(|| die!("Malformed undo journal entry: {}", line));
+
+        // Undoing moves each file from where the original rename left it
+        // (`after`) back to where it started (`before`).
+        let full_path_before = PathBuf::from(after);
+        let full_path_after = PathBuf::from(before);
+        let filename_before = full_path_before
+            .file_name()
+            .unwrap_or_else(|| die!("Malformed undo journal entry: {}", line))
+            .to_owned();
+        let filename_after = full_path_after
+            .file_name()
+            .unwrap_or_else(|| die!("Malformed undo journal entry: {}", line))
+            .to_owned();
+
+        files.push(FileToRename {
+            full_path_before,
+            full_path_after,
+            filename_before,
+            filename_after,
+            outcome: FileOutcome::Unchanged,
+        });
+    }
+
+    if files.is_empty() {
+        die!("Undo journal at {} was empty.", journal_path.display());
+    }
+
+    files
+}
+
+fn apply_find_replace(args: &Arguments, files: &mut Vec<FileToRename>) {
+    let find = args.find.as_ref().unwrap();
+    let replace = args.replace.as_ref().unwrap();
+
+    let re = Regex::new(find).unwrap_or_else(|_| die!("Invalid --find regex '{}'.", find));
+
+    for file in files.iter_mut() {
+        let filename_before = file
+            .filename_before
+            .to_str()
+            .unwrap_or_else(|| die!("Unable to get string for filename."));
+
+        let replaced = re.replace_all(filename_before, replace.as_str()).into_owned();
+
+        file.filename_after = if args.include_extensions {
+            OsString::from(replaced)
+        } else {
+            let extension = file.full_path_before.extension();
+            PathBuf::from(replaced)
+                .with_extension(extension.unwrap_or_default())
+                .into()
+        };
+        file.full_path_after = file.full_path_before.with_file_name(&file.filename_after);
+    }
+}
+
+// Replaces every character other than ASCII letters, digits, `.`, `-` and `_`
+// (whitespace, shell-hostile characters and non-ASCII letters alike) with
+// `args.separator`, collapses repeated separators, and trims them from both
+// ends. Letters are lowercased unless `--keep-case` is given.
+fn sanitize_name(input: &str, args: &Arguments) -> String {
+    let separator = args.separator.as_str();
+    let mut output = String::new();
+    let mut last_was_separator = true;
+
+    for c in input.chars() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' {
+            if args.keep_case {
+                output.push(c);
+            } else {
+                output.extend(c.to_lowercase());
+            }
+            last_was_separator = false;
+        } else if !last_was_separator && !separator.is_empty() {
+            output.push_str(separator);
+            last_was_separator = true;
+        }
+    }
+
+    if !separator.is_empty() {
+        // Separators already present in the input are kept as ordinary
+        // characters above, so collapse and trim them here as well.
+        let doubled = separator.repeat(2);
+        while output.contains(&doubled) {
+            output = output.replace(&doubled, separator);
+        }
+        while output.starts_with(separator) {
+            output.drain(..separator.len());
+        }
+        while output.ends_with(separator) {
+            output.truncate(output.len() - separator.len());
+        }
+    }
+
+    output
+}
+
+fn apply_sanitize(args: &Arguments, files: &mut Vec<FileToRename>) {
+    for file in files.iter_mut() {
+        let filename_before = file
+            .filename_before
+            .to_str()
+            .unwrap_or_else(|| die!("Unable to get string for filename."));
+
+        let sanitized = sanitize_name(filename_before, args);
+
+        file.filename_after = if args.include_extensions {
+            OsString::from(sanitized)
+        } else {
+            let extension = file.full_path_before.extension();
+            PathBuf::from(sanitized)
+                .with_extension(extension.unwrap_or_default())
+                .into()
+        };
+        file.full_path_after = file.full_path_before.with_file_name(&file.filename_after);
+    }
+}
+
+fn editor_command(args: &Arguments) -> String {
+    if let Some(editor) = &args.editor {
+        return editor.clone();
+    }
+    if let Ok(visual) = std::env::var("VISUAL") {
+        if !visual.is_empty() {
+            return visual;
+        }
+    }
+    if let Ok(editor) = std::env::var("EDITOR") {
+        if !editor.is_empty() {
+            return editor;
+        }
+    }
+    if cfg!(windows) {
+        "notepad".to_string()
+    } else {
+        "vi".to_string()
+    }
+}
+
+fn open_editor_on_buffer(args: &Arguments, buffer_filename: &Path) {
+    let editor = editor_command(args);
+
+    // `$VISUAL`/`$EDITOR`/`--editor` may carry arguments of their own (e.g.
+    // `vim -p`, `code --wait`), so run them through a shell like
+    // vidir/vipe do, rather than treating the whole string as one binary.
+    let status = if cfg!(windows) {
+        Command::new("cmd")
+            .arg("/C")
+            .arg(format!("{} \"{}\"", editor, buffer_filename.display()))
+            .status()
+    } else {
+        Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$1\"", editor))
+            .arg("--")
+            .arg(buffer_filename)
+            .status()
+    };
+
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => die!(
+            "Editor '{}' exited with a non-zero status ({}), aborting.",
+            editor,
+            status.code().map_or("unknown".to_string(), |c| c.to_string())
+        ),
+        Err(_) => die!("Unable to launch editor '{}'.", editor),
+    }
+}
+
 fn read_filenames_from_buffer(
     buffer_filename: &Path,
     files: &mut Vec<FileToRename>,
@@ -235,18 +522,249 @@ fn validate_filenames(
 }
 
 
-fn execute_rename(args: &Arguments, files: &mut Vec<FileToRename>) {
-    fn rename_file_if_safe(p: &Path, q: &Path) -> Result<(), ()> {
-        if q.exists() {
-            return Err(());
-        };
-        match fs::rename(p, q) {
-            Ok(_) => Ok(()),
-            Err(_) => Err(()),
+#[derive(Clone)]
+enum RenameStep {
+    Direct(usize),
+    ToTemp(usize, PathBuf),
+    FromTemp(usize, PathBuf),
+}
+
+fn unique_temp_path(original: &Path) -> PathBuf {
+    let dir = original.parent().unwrap_or_else(|| Path::new("."));
+    let base = original
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("rename-tmp");
+
+    let seed: u64 = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64)
+        .unwrap_or(0)
+        ^ (std::process::id() as u64);
+
+    // The seed alone can repeat across iterations within the same instant;
+    // folding in a monotonic attempt counter guarantees each candidate is
+    // distinct so the loop always makes progress.
+    for attempt in 0u64.. {
+        let candidate = dir.join(format!(".{}.{:x}.renametmp", base, seed.wrapping_add(attempt)));
+        if !candidate.exists() {
+            return candidate;
+        }
+    }
+
+    unreachable!()
+}
+
+// Plans a rename order that never needs to overwrite a file still occupied by
+// another pending rename. Files whose `full_path_after` lands on a path that
+// another pending rename is about to vacate are ordered so the vacating
+// rename happens first; files that form a cycle (e.g. `a -> b`, `b -> a`) are
+// staged through a unique temporary name instead, since no linear order can
+// satisfy them.
+fn plan_rename_order(files: &[FileToRename]) -> Vec<RenameStep> {
+    let active: Vec<usize> = (0..files.len())
+        .filter(|&i| files[i].full_path_after != files[i].full_path_before)
+        .collect();
+
+    let mut before_to_active_index = std::collections::HashMap::new();
+    for &i in &active {
+        before_to_active_index.insert(files[i].full_path_before.clone(), i);
+    }
+
+    // edge b -> a: b must be renamed before a, because a wants to move into
+    // the path that b currently occupies.
+    let mut blocked_by: std::collections::HashMap<usize, Vec<usize>> = std::collections::HashMap::new();
+    let mut in_degree: std::collections::HashMap<usize, usize> =
+        active.iter().map(|&i| (i, 0)).collect();
+
+    for &a in &active {
+        if let Some(&b) = before_to_active_index.get(&files[a].full_path_after) {
+            if b != a {
+                blocked_by.entry(b).or_default().push(a);
+                *in_degree.get_mut(&a).unwrap() += 1;
+            }
+        }
+    }
+
+    let mut ready: Vec<usize> = active
+        .iter()
+        .copied()
+        .filter(|i| in_degree[i] == 0)
+        .collect();
+    ready.sort();
+
+    let mut steps = Vec::new();
+    let mut done = vec![false; files.len()];
+
+    while let Some(i) = ready.pop() {
+        steps.push(RenameStep::Direct(i));
+        done[i] = true;
+        if let Some(dependents) = blocked_by.get(&i) {
+            for &a in dependents {
+                let deg = in_degree.get_mut(&a).unwrap();
+                *deg -= 1;
+                if *deg == 0 {
+                    ready.push(a);
+                }
+            }
+        }
+    }
+
+    // Anything left over is part of a dependency cycle or waits on one: stage
+    // every such file through a temporary name first, then move each temp
+    // file into place.
+    let mut remaining: Vec<usize> = active.iter().copied().filter(|&i| !done[i]).collect();
+    remaining.sort();
+
+    let mut temp_paths = Vec::new();
+    for &i in &remaining {
+        let temp_path = unique_temp_path(&files[i].full_path_before);
+        steps.push(RenameStep::ToTemp(i, temp_path.clone()));
+        temp_paths.push((i, temp_path));
+    }
+    for (i, temp_path) in temp_paths {
+        steps.push(RenameStep::FromTemp(i, temp_path));
+    }
+
+    steps
+}
+
+enum RenameError {
+    WouldOverwrite,
+    Failed,
+}
+
+fn rename_file_if_safe(p: &Path, q: &Path, overwrite: bool) -> Result<(), RenameError> {
+    if !overwrite && q.exists() {
+        return Err(RenameError::WouldOverwrite);
+    }
+    fs::rename(p, q).map_err(|_| RenameError::Failed)
+}
+
+fn read_stuck_answer(prompt: &str) -> String {
+    print!("{}", prompt);
+    std::io::stdout().flush().ok();
+    let mut input = String::new();
+    if std::io::stdin().read_line(&mut input).is_err() {
+        return "a".to_string();
+    }
+    input.trim().to_lowercase()
+}
+
+fn prompt_action_when_stuck(message: &str) -> ActionWhenStuck {
+    loop {
+        println!("{} {}", "STUCK.".yellow(), message);
+        match read_stuck_answer("[R]etry, [S]kip, [A]bort, roll[B]ack? ").as_str() {
+            "r" | "retry" => return ActionWhenStuck::Retry,
+            "s" | "skip" => return ActionWhenStuck::Skip,
+            "a" | "abort" => return ActionWhenStuck::Abort,
+            "b" | "rollback" => return ActionWhenStuck::Rollback,
+            _ => println!("Please answer R, S, A, or B."),
+        }
+    }
+}
+
+fn prompt_action_when_stuck_rolling_back(message: &str) -> ActionWhenStuckRollingBack {
+    loop {
+        println!("{} {}", "STUCK.".yellow(), message);
+        match read_stuck_answer("[R]etry, [S]kip, [A]bort rollback? ").as_str() {
+            "r" | "retry" => return ActionWhenStuckRollingBack::Retry,
+            "s" | "skip" => return ActionWhenStuckRollingBack::Skip,
+            "a" | "abort" => return ActionWhenStuckRollingBack::AbortRollback,
+            _ => println!("Please answer R, S, or A."),
         }
     }
+}
 
-    if args.dry_run == true {
+// Resolves what to do about a stuck rename. `--prompt never` never blocks on
+// input: it behaves as if the user always answered in the affirmative, which
+// in practice means skipping anything `--overwrite` doesn't already permit.
+fn resolve_stuck_action(args: &Arguments, message: &str) -> ActionWhenStuck {
+    match args.prompt {
+        PromptMode::Never => ActionWhenStuck::Skip,
+        PromptMode::Always | PromptMode::Error => prompt_action_when_stuck(message),
+    }
+}
+
+fn resolve_stuck_rollback_action(args: &Arguments, message: &str) -> ActionWhenStuckRollingBack {
+    match args.prompt {
+        PromptMode::Never => ActionWhenStuckRollingBack::Skip,
+        PromptMode::Always | PromptMode::Error => prompt_action_when_stuck_rolling_back(message),
+    }
+}
+
+enum StepOutcome {
+    Renamed,
+    Skipped,
+    RollbackRequested,
+    Abort(String),
+}
+
+// Never `die!`s directly: an abort in the middle of a cycle's temp-staged
+// renames must let the caller restore any already-parked temp files and
+// write the journal for whatever did complete before the process exits.
+fn perform_rename_with_recovery(args: &Arguments, p: &Path, q: &Path) -> StepOutcome {
+    if args.prompt == PromptMode::Always {
+        let message = format!("About to rename {} -> {}.", p.display(), q.display());
+        match prompt_action_when_stuck(&message) {
+            ActionWhenStuck::Skip => return StepOutcome::Skipped,
+            ActionWhenStuck::Abort => return StepOutcome::Abort("Aborted.".to_string()),
+            ActionWhenStuck::Rollback => return StepOutcome::RollbackRequested,
+            ActionWhenStuck::Retry => {}
+        }
+    }
+
+    loop {
+        match rename_file_if_safe(p, q, args.overwrite) {
+            Ok(_) => return StepOutcome::Renamed,
+            Err(reason) => {
+                let message = match reason {
+                    RenameError::WouldOverwrite => format!(
+                        "{} already exists; refusing to overwrite it with {} (use --overwrite to allow).",
+                        q.display(),
+                        p.display()
+                    ),
+                    RenameError::Failed => {
+                        format!("Unable to rename {} to {}.", p.display(), q.display())
+                    }
+                };
+
+                match resolve_stuck_action(args, &message) {
+                    ActionWhenStuck::Retry => continue,
+                    ActionWhenStuck::Skip => return StepOutcome::Skipped,
+                    ActionWhenStuck::Abort => return StepOutcome::Abort(message),
+                    ActionWhenStuck::Rollback => return StepOutcome::RollbackRequested,
+                }
+            }
+        }
+    }
+}
+
+// Moves a cycle member that never reached its `FromTemp` leg back from its
+// temporary name to where it started, rather than leaving it stranded under
+// a hidden `.<name>.<hex>.renametmp` filename.
+fn restore_parked_file(args: &Arguments, temp_path: &Path, original_path: &Path) {
+    loop {
+        match rename_file_if_safe(temp_path, original_path, args.overwrite) {
+            Ok(_) => return,
+            Err(_) => {
+                let message = format!(
+                    "Unable to restore {} back to {} after an interrupted cycle rename.",
+                    temp_path.display(),
+                    original_path.display()
+                );
+                match resolve_stuck_rollback_action(args, &message) {
+                    ActionWhenStuckRollingBack::Retry => continue,
+                    ActionWhenStuckRollingBack::Skip => return,
+                    ActionWhenStuckRollingBack::AbortRollback => die!("{}", message),
+                }
+            }
+        }
+    }
+}
+
+fn execute_rename(args: &Arguments, files: &mut Vec<FileToRename>) {
+    if args.dry_run {
         for file in files {
             println!(
                 "{} -> {}",
@@ -257,47 +775,105 @@ fn execute_rename(args: &Arguments, files: &mut Vec<FileToRename>) {
         exit(0);
     }
 
-    let mut index = 0;
-    let mut rollback = false;
-    while index < files.len() {
-        let file = &mut files[index];
-
+    for file in files.iter_mut() {
         if file.full_path_after == file.full_path_before {
             file.outcome = FileOutcome::RenameWasNoop;
-            index += 1;
-            continue;
         }
+    }
+
+    let steps = plan_rename_order(files);
+    let mut rollback = false;
+    let mut abort_message: Option<String> = None;
+    // Order in which files actually reached their final resting place, so a
+    // later rollback can undo them in the exact reverse order they happened.
+    let mut renamed_order: Vec<usize> = Vec::new();
+    // Cycle members currently sitting at a temp name, waiting on their
+    // `FromTemp` leg.
+    let mut parked: Vec<(usize, PathBuf)> = Vec::new();
+
+    for step in steps {
+        if rollback || abort_message.is_some() {
+            break;
+        }
+
+        let (i, from, to): (usize, PathBuf, PathBuf) = match step.clone() {
+            RenameStep::Direct(i) => (
+                i,
+                files[i].full_path_before.clone(),
+                files[i].full_path_after.clone(),
+            ),
+            RenameStep::ToTemp(i, temp_path) => (i, files[i].full_path_before.clone(), temp_path),
+            RenameStep::FromTemp(i, temp_path) => (i, temp_path, files[i].full_path_after.clone()),
+        };
 
-        match rename_file_if_safe(&file.full_path_before, &file.full_path_after) {
-            Ok(_) => {
-                file.outcome = FileOutcome::Renamed;
-                index += 1;
+        match perform_rename_with_recovery(args, &from, &to) {
+            StepOutcome::Renamed => match step {
+                RenameStep::Direct(_) => {
+                    files[i].outcome = FileOutcome::Renamed;
+                    renamed_order.push(i);
+                }
+                RenameStep::ToTemp(_, temp_path) => {
+                    parked.push((i, temp_path));
+                }
+                RenameStep::FromTemp(_, _) => {
+                    files[i].outcome = FileOutcome::Renamed;
+                    renamed_order.push(i);
+                    parked.retain(|&(parked_i, _)| parked_i != i);
+                }
+            },
+            StepOutcome::Skipped => {
+                files[i].outcome = FileOutcome::Unchanged;
+            }
+            StepOutcome::RollbackRequested => {
+                rollback = true;
+            }
+            StepOutcome::Abort(message) => {
+                abort_message = Some(message);
             }
-            Err(_) => die!("file renaming was not safe"),
         }
     }
 
-    if rollback == true {
-        println!("Undoing renames...");
+    // Whether we finished, were asked to roll back, or are about to abort,
+    // nothing should still be sitting under a temp name afterwards.
+    for (i, temp_path) in parked.drain(..) {
+        restore_parked_file(args, &temp_path, &files[i].full_path_before);
+        files[i].outcome = FileOutcome::Unchanged;
+    }
 
-        index = 0;
-        while index < files.len() {
-            let file = &mut files[index];
-            if file.outcome != FileOutcome::Renamed {
-                index += 1;
-                continue;
-            }
+    if let Some(message) = abort_message {
+        write_rename_journal(files);
+        die!("{}", message);
+    }
 
-            match rename_file_if_safe(&file.full_path_after, &file.full_path_before) {
-                Ok(_) => {
-                    file.outcome = FileOutcome::Unchanged;
-                    index += 1;
-                    continue;
+    if rollback {
+        println!("Undoing renames...");
+
+        for i in renamed_order.into_iter().rev() {
+            let file = &mut files[i];
+            loop {
+                match rename_file_if_safe(&file.full_path_after, &file.full_path_before, args.overwrite) {
+                    Ok(_) => {
+                        file.outcome = FileOutcome::Unchanged;
+                        break;
+                    }
+                    Err(_) => {
+                        let message = format!(
+                            "Unable to undo rename of {} back to {}.",
+                            file.full_path_after.display(),
+                            file.full_path_before.display()
+                        );
+                        match resolve_stuck_rollback_action(args, &message) {
+                            ActionWhenStuckRollingBack::Retry => continue,
+                            ActionWhenStuckRollingBack::Skip => break,
+                            ActionWhenStuckRollingBack::AbortRollback => die!("{}", message),
+                        }
+                    }
                 }
-                Err(_) => die!("file renaming was not safe"),
             }
         }
     }
+
+    write_rename_journal(files);
 }
 
 fn print_state(files: &Vec<FileToRename>) {